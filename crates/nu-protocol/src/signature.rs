@@ -1,9 +1,12 @@
 use crate::syntax_shape::SyntaxShape;
 use crate::type_shape::Type;
-use indexmap::IndexMap;
 use nu_source::{b, DebugDocBuilder, PrettyDebug, PrettyDebugWithSource};
 use serde::{Deserialize, Serialize};
 
+/// Uniquely identifies a variable slot in a stack frame. Parameters bound to a
+/// `var_id` become in-scope variables when a command with `creates_scope` set runs.
+pub type VarId = usize;
+
 /// The types of named parameter that a command can have
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum NamedType {
@@ -19,20 +22,22 @@ pub enum NamedType {
 /// The type of positional arguments
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PositionalType {
-    /// A mandatory postional argument with the expected shape of the value
-    Mandatory(String, SyntaxShape),
-    /// An optional positional argument with the expected shape of the value
-    Optional(String, SyntaxShape),
+    /// A mandatory postional argument with the expected shape of the value, and the
+    /// variable slot it's bound to once the owning command creates a scope
+    Mandatory(String, SyntaxShape, Option<VarId>),
+    /// An optional positional argument with the expected shape of the value, and the
+    /// variable slot it's bound to once the owning command creates a scope
+    Optional(String, SyntaxShape, Option<VarId>),
 }
 
 impl PrettyDebug for PositionalType {
     /// Prepare the PositionalType for pretty-printing
     fn pretty(&self) -> DebugDocBuilder {
         match self {
-            PositionalType::Mandatory(string, shape) => {
+            PositionalType::Mandatory(string, shape, _) => {
                 b::description(string) + b::delimit("(", shape.pretty(), ")").into_kind().group()
             }
-            PositionalType::Optional(string, shape) => {
+            PositionalType::Optional(string, shape, _) => {
                 b::description(string)
                     + b::operator("?")
                     + b::delimit("(", shape.pretty(), ")").into_kind().group()
@@ -44,48 +49,108 @@ impl PrettyDebug for PositionalType {
 impl PositionalType {
     /// Helper to create a mandatory positional argument type
     pub fn mandatory(name: &str, ty: SyntaxShape) -> PositionalType {
-        PositionalType::Mandatory(name.to_string(), ty)
+        PositionalType::Mandatory(name.to_string(), ty, None)
     }
 
     /// Helper to create a mandatory positional argument with an "any" type
     pub fn mandatory_any(name: &str) -> PositionalType {
-        PositionalType::Mandatory(name.to_string(), SyntaxShape::Any)
+        PositionalType::Mandatory(name.to_string(), SyntaxShape::Any, None)
     }
 
     /// Helper to create a mandatory positional argument with a block type
     pub fn mandatory_block(name: &str) -> PositionalType {
-        PositionalType::Mandatory(name.to_string(), SyntaxShape::Block)
+        PositionalType::Mandatory(name.to_string(), SyntaxShape::Block, None)
     }
 
     /// Helper to create a optional positional argument type
     pub fn optional(name: &str, ty: SyntaxShape) -> PositionalType {
-        PositionalType::Optional(name.to_string(), ty)
+        PositionalType::Optional(name.to_string(), ty, None)
     }
 
     /// Helper to create a optional positional argument with an "any" type
     pub fn optional_any(name: &str) -> PositionalType {
-        PositionalType::Optional(name.to_string(), SyntaxShape::Any)
+        PositionalType::Optional(name.to_string(), SyntaxShape::Any, None)
     }
 
     /// Gets the name of the positional argument
     pub fn name(&self) -> &str {
         match self {
-            PositionalType::Mandatory(s, _) => s,
-            PositionalType::Optional(s, _) => s,
+            PositionalType::Mandatory(s, _, _) => s,
+            PositionalType::Optional(s, _, _) => s,
         }
     }
 
     /// Gets the expected type of a positional argument
     pub fn syntax_type(&self) -> SyntaxShape {
         match *self {
-            PositionalType::Mandatory(_, t) => t,
-            PositionalType::Optional(_, t) => t,
+            PositionalType::Mandatory(_, t, _) => t,
+            PositionalType::Optional(_, t, _) => t,
+        }
+    }
+
+    /// Gets the variable slot this positional argument is bound to, if the owning
+    /// signature creates a scope
+    pub fn var_id(&self) -> Option<VarId> {
+        match *self {
+            PositionalType::Mandatory(_, _, var_id) => var_id,
+            PositionalType::Optional(_, _, var_id) => var_id,
+        }
+    }
+
+    /// Bind this positional argument to a variable slot
+    pub fn with_var_id(self, var_id: VarId) -> PositionalType {
+        match self {
+            PositionalType::Mandatory(name, shape, _) => {
+                PositionalType::Mandatory(name, shape, Some(var_id))
+            }
+            PositionalType::Optional(name, shape, _) => {
+                PositionalType::Optional(name, shape, Some(var_id))
+            }
         }
     }
 }
 
 type Description = String;
 
+/// A named flag (eg `--foo`), its optional single-character short alias (eg `-f`),
+/// its expected argument shape, and its help text
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Flag {
+    /// The long name of the flag, used as `--long`
+    pub long: String,
+    /// An optional short alias for the flag, used as `-short`
+    pub short: Option<char>,
+    /// The type of the named flag
+    pub named_type: NamedType,
+    /// Help text describing the flag
+    pub desc: Description,
+    /// The variable slot this flag is bound to once the owning command creates a scope
+    pub var_id: Option<VarId>,
+}
+
+impl Flag {
+    /// Bind this named flag to a variable slot
+    pub fn with_var_id(mut self, var_id: VarId) -> Flag {
+        self.var_id = Some(var_id);
+        self
+    }
+}
+
+/// The category a command belongs to, used to group and sort commands in help
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub enum Category {
+    Default,
+    Conversions,
+    Core,
+    Env,
+    Filters,
+    FileSystem,
+    Math,
+    Strings,
+    System,
+    Custom(String),
+}
+
 /// The full signature of a command. All commands have a signature similar to a function signature.
 /// Commands will use this information to register themselves with Nu's core engine so that the command
 /// can be invoked, help can be displayed, and calls to the command can be error-checked.
@@ -95,18 +160,26 @@ pub struct Signature {
     pub name: String,
     /// Usage instructions about the command
     pub usage: String,
+    /// A longer, multi-paragraph description shown on `help <command>`, in addition to `usage`
+    pub extra_usage: String,
+    /// The category this command belongs to, for grouping in help
+    pub category: Category,
+    /// Synonyms/keywords for the command, matched during fuzzy command discovery
+    pub search_terms: Vec<String>,
     /// The list of positional arguments, both required and optional, and their corresponding types and help text
     pub positional: Vec<(PositionalType, Description)>,
     /// After the positional arguments, a catch-all for the rest of the arguments that might follow, their type, and help text
     pub rest_positional: Option<(SyntaxShape, Description)>,
-    /// The named flags with corresponding type and help text
-    pub named: IndexMap<String, (NamedType, Description)>,
-    /// The type of values being sent out from the command into the pipeline, if any
-    pub yields: Option<Type>,
-    /// The type of values being read in from the pipeline into the command, if any
-    pub input: Option<Type>,
+    /// The named flags, in declaration order, each with its long/short names, type, and help text
+    pub named: Vec<Flag>,
+    /// The input/output type pairs this command supports, so a command can behave
+    /// differently depending on what type of value is flowing through the pipeline
+    pub input_output_types: Vec<(Type, Type)>,
     /// If the command is expected to filter data, or to consume it (as a sink)
     pub is_filter: bool,
+    /// Whether invoking this command creates a new variable scope, binding its
+    /// positional and named parameters to variable slots in a stack frame
+    pub creates_scope: bool,
 }
 
 impl PrettyDebugWithSource for Signature {
@@ -134,12 +207,21 @@ impl Signature {
         Signature {
             name: name.into(),
             usage: String::new(),
+            extra_usage: String::new(),
+            category: Category::Default,
+            search_terms: vec![],
             positional: vec![],
             rest_positional: None,
-            named: indexmap::indexmap! {"help".into() => (NamedType::Help, "Display this help message".into())},
+            named: vec![Flag {
+                long: "help".into(),
+                short: Some('h'),
+                named_type: NamedType::Help,
+                desc: "Display this help message".into(),
+                var_id: None,
+            }],
             is_filter: false,
-            yields: None,
-            input: None,
+            input_output_types: vec![],
+            creates_scope: false,
         }
     }
 
@@ -154,6 +236,24 @@ impl Signature {
         self
     }
 
+    /// Add a longer, extended description to the signature, shown on `help <command>`
+    pub fn extra_usage(mut self, extra_usage: impl Into<String>) -> Signature {
+        self.extra_usage = extra_usage.into();
+        self
+    }
+
+    /// Assign a category to the signature, used to group commands in help
+    pub fn category(mut self, category: Category) -> Signature {
+        self.category = category;
+        self
+    }
+
+    /// Add search terms (synonyms/keywords) to the signature, used for fuzzy command discovery
+    pub fn search_terms(mut self, terms: Vec<String>) -> Signature {
+        self.search_terms = terms;
+        self
+    }
+
     /// Add a required positional argument to the signature
     pub fn required(
         mut self,
@@ -162,7 +262,7 @@ impl Signature {
         desc: impl Into<String>,
     ) -> Signature {
         self.positional.push((
-            PositionalType::Mandatory(name.into(), ty.into()),
+            PositionalType::Mandatory(name.into(), ty.into(), None),
             desc.into(),
         ));
 
@@ -177,54 +277,149 @@ impl Signature {
         desc: impl Into<String>,
     ) -> Signature {
         self.positional.push((
-            PositionalType::Optional(name.into(), ty.into()),
+            PositionalType::Optional(name.into(), ty.into(), None),
             desc.into(),
         ));
 
         self
     }
 
+    /// Mark this signature as creating a new variable scope when invoked, so its
+    /// positional and named parameters can be bound to variable slots
+    pub fn creates_scope(mut self) -> Signature {
+        self.creates_scope = true;
+        self
+    }
+
+    /// Gets a positional argument together with its description, by index
+    pub fn get_positional(&self, position: usize) -> Option<&(PositionalType, Description)> {
+        self.positional.get(position)
+    }
+
     /// Add an optional named flag argument to the signature
     pub fn named(
+        self,
+        name: impl Into<String>,
+        ty: impl Into<SyntaxShape>,
+        desc: impl Into<String>,
+    ) -> Signature {
+        self.named_short(name, ty, desc, None)
+    }
+
+    /// Add an optional named flag argument, with a short alias, to the signature
+    pub fn named_short(
         mut self,
         name: impl Into<String>,
         ty: impl Into<SyntaxShape>,
         desc: impl Into<String>,
+        short: Option<char>,
     ) -> Signature {
-        self.named
-            .insert(name.into(), (NamedType::Optional(ty.into()), desc.into()));
+        self.push_flag(Flag {
+            long: name.into(),
+            short,
+            named_type: NamedType::Optional(ty.into()),
+            desc: desc.into(),
+            var_id: None,
+        });
 
         self
     }
 
     /// Add a required named flag argument to the signature
     pub fn required_named(
+        self,
+        name: impl Into<String>,
+        ty: impl Into<SyntaxShape>,
+        desc: impl Into<String>,
+    ) -> Signature {
+        self.required_named_short(name, ty, desc, None)
+    }
+
+    /// Add a required named flag argument, with a short alias, to the signature
+    pub fn required_named_short(
         mut self,
         name: impl Into<String>,
         ty: impl Into<SyntaxShape>,
         desc: impl Into<String>,
+        short: Option<char>,
     ) -> Signature {
-        self.named
-            .insert(name.into(), (NamedType::Mandatory(ty.into()), desc.into()));
+        self.push_flag(Flag {
+            long: name.into(),
+            short,
+            named_type: NamedType::Mandatory(ty.into()),
+            desc: desc.into(),
+            var_id: None,
+        });
 
         self
     }
 
     /// Add a switch to the signature
-    pub fn switch(mut self, name: impl Into<String>, desc: impl Into<String>) -> Signature {
-        self.named
-            .insert(name.into(), (NamedType::Switch, desc.into()));
+    pub fn switch(self, name: impl Into<String>, desc: impl Into<String>) -> Signature {
+        self.switch_short(name, desc, None)
+    }
+
+    /// Add a switch, with a short alias, to the signature
+    pub fn switch_short(
+        mut self,
+        name: impl Into<String>,
+        desc: impl Into<String>,
+        short: Option<char>,
+    ) -> Signature {
+        self.push_flag(Flag {
+            long: name.into(),
+            short,
+            named_type: NamedType::Switch,
+            desc: desc.into(),
+            var_id: None,
+        });
 
         self
     }
 
+    /// Register a named flag, overwriting the value of any existing flag with the same
+    /// long name in place (matching the overwrite-on-duplicate-key behavior the old
+    /// `IndexMap<String, _>` gave us, including preserving its position in `named`), and
+    /// rejecting a short alias that's already claimed by a different flag.
+    fn push_flag(&mut self, flag: Flag) {
+        if let Some(short) = flag.short {
+            if let Some(existing) = self.get_short_flag(short) {
+                if existing.long != flag.long {
+                    panic!(
+                        "signature `{}`: short flag -{} is already registered for --{}",
+                        self.name, short, existing.long
+                    );
+                }
+            }
+        }
+
+        match self
+            .named
+            .iter()
+            .position(|existing| existing.long == flag.long)
+        {
+            Some(index) => self.named[index] = flag,
+            None => self.named.push(flag),
+        }
+    }
+
     /// Remove the default help switch
     pub fn remove_help(mut self) -> Signature {
-        self.named.remove("help");
+        self.named.retain(|flag| flag.long != "help");
 
         self
     }
 
+    /// Look up a named flag by its long name
+    pub fn get_long_flag(&self, name: &str) -> Option<&Flag> {
+        self.named.iter().find(|flag| flag.long == name)
+    }
+
+    /// Look up a named flag by its short alias
+    pub fn get_short_flag(&self, short: char) -> Option<&Flag> {
+        self.named.iter().find(|flag| flag.short == Some(short))
+    }
+
     /// Set the filter flag for the signature
     pub fn filter(mut self) -> Signature {
         self.is_filter = true;
@@ -237,15 +432,154 @@ impl Signature {
         self
     }
 
-    /// Add a type for the output of the command to the signature
+    /// Add a set of input/output type pairs to the signature
+    pub fn input_output_types(mut self, pairs: Vec<(Type, Type)>) -> Signature {
+        self.input_output_types.extend(pairs);
+        self
+    }
+
+    /// Add a type for the output of the command to the signature.
+    /// Convenience wrapper around `input_output_types`: if the most recent pair still
+    /// has an unset (`Type::Any`) output, this fills it in, so `.input(x).yields(y)`
+    /// produces the single precise pair `(x, y)` rather than two independent wildcard pairs.
     pub fn yields(mut self, ty: Type) -> Signature {
-        self.yields = Some(ty);
+        match self.input_output_types.last_mut() {
+            Some((_, output)) if *output == Type::Any => *output = ty,
+            _ => self.input_output_types.push((Type::Any, ty)),
+        }
         self
     }
 
-    /// Add a type for the input of the command to the signature
+    /// Add a type for the input of the command to the signature.
+    /// Convenience wrapper around `input_output_types`: if the most recent pair still
+    /// has an unset (`Type::Any`) input, this fills it in, so `.yields(y).input(x)`
+    /// produces the single precise pair `(x, y)` rather than two independent wildcard pairs.
     pub fn input(mut self, ty: Type) -> Signature {
-        self.input = Some(ty);
+        match self.input_output_types.last_mut() {
+            Some((input, _)) if *input == Type::Any => *input = ty,
+            _ => self.input_output_types.push((ty, Type::Any)),
+        }
         self
     }
+
+    /// Render a human-readable invocation form of the signature, eg)
+    /// `foo <required:int> (optional:string) --flag(-f) (string) ...rest:any`
+    pub fn call_signature(&self) -> String {
+        let mut text = String::new();
+        text.push_str(&self.name);
+        text.push(' ');
+
+        for (positional, _) in &self.positional {
+            match positional {
+                PositionalType::Mandatory(name, shape, _) => {
+                    text.push_str(&format!("<{}:{}> ", name, format_shape(shape)));
+                }
+                PositionalType::Optional(name, shape, _) => {
+                    text.push_str(&format!("({}:{}) ", name, format_shape(shape)));
+                }
+            }
+        }
+
+        for flag in &self.named {
+            text.push_str(&format!("--{}", flag.long));
+            if let Some(short) = flag.short {
+                text.push_str(&format!("(-{})", short));
+            }
+            match &flag.named_type {
+                NamedType::Switch | NamedType::Help => {}
+                NamedType::Mandatory(shape) => {
+                    text.push_str(&format!(" <{}>", format_shape(shape)));
+                }
+                NamedType::Optional(shape) => {
+                    text.push_str(&format!(" ({})", format_shape(shape)));
+                }
+            }
+            text.push(' ');
+        }
+
+        if let Some((shape, _)) = &self.rest_positional {
+            text.push_str(&format!("...rest:{}", format_shape(shape)));
+        } else {
+            text.pop();
+        }
+
+        text
+    }
+}
+
+/// Render a `SyntaxShape` the way `call_signature` expects to display it
+fn format_shape(shape: &SyntaxShape) -> String {
+    // `SyntaxShape`'s `Debug` output is only safe to use verbatim for unit variants; a
+    // data-carrying variant like `Custom("foo")` would otherwise leak its quoted payload
+    // into the call signature. Keep just the variant name.
+    let debug = format!("{:?}", shape);
+    let variant_name = debug.split('(').next().unwrap_or(&debug);
+    variant_name.to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overwriting_a_flag_updates_it_in_place() {
+        let sig = Signature::new("test")
+            .remove_help()
+            .switch("a", "a desc")
+            .switch("b", "b desc")
+            .switch("c", "c desc")
+            .switch("a", "a desc updated");
+
+        let longs: Vec<&str> = sig.named.iter().map(|flag| flag.long.as_str()).collect();
+        assert_eq!(longs, vec!["a", "b", "c"]);
+        assert_eq!(sig.get_long_flag("a").unwrap().desc, "a desc updated");
+    }
+
+    #[test]
+    #[should_panic(expected = "short flag -a is already registered")]
+    fn registering_a_short_alias_twice_panics() {
+        Signature::new("test")
+            .remove_help()
+            .switch_short("alpha", "desc", Some('a'))
+            .switch_short("beta", "desc", Some('a'));
+    }
+
+    #[test]
+    fn input_then_yields_merges_into_one_pair() {
+        let sig = Signature::new("test").input(Type::Any).yields(Type::Any);
+        assert_eq!(sig.input_output_types.len(), 1);
+    }
+
+    #[test]
+    fn yields_then_input_merges_into_one_pair() {
+        let sig = Signature::new("test").yields(Type::Any).input(Type::Any);
+        assert_eq!(sig.input_output_types.len(), 1);
+    }
+
+    #[test]
+    fn explicit_input_output_types_are_not_merged() {
+        let sig = Signature::new("test")
+            .input_output_types(vec![(Type::Any, Type::Any)])
+            .input_output_types(vec![(Type::Any, Type::Any)]);
+        assert_eq!(sig.input_output_types.len(), 2);
+    }
+
+    #[test]
+    fn positional_type_can_be_bound_to_a_var_id() {
+        let positional = PositionalType::mandatory_any("x");
+        assert_eq!(positional.var_id(), None);
+
+        let positional = positional.with_var_id(42);
+        assert_eq!(positional.var_id(), Some(42));
+    }
+
+    #[test]
+    fn flag_can_be_bound_to_a_var_id() {
+        let sig = Signature::new("test").remove_help().switch("a", "desc");
+        let flag = sig.named[0].clone();
+        assert_eq!(flag.var_id, None);
+
+        let flag = flag.with_var_id(7);
+        assert_eq!(flag.var_id, Some(7));
+    }
 }